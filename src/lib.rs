@@ -0,0 +1,1286 @@
+use csv::{ReaderBuilder, WriterBuilder};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Write;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum TransactionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Transfer,
+}
+
+/// The raw shape of a CSV row, before type-specific validation. `amount` is
+/// `Some` for deposits/withdrawals/transfers and should be `None` for
+/// everything else; `to` is `Some` only for transfers. `Transaction::try_from`
+/// enforces both.
+#[derive(Debug, Deserialize)]
+struct TransactionRecord {
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    client: u16,
+    tx: u32,
+    amount: Option<Decimal>,
+    #[serde(default)]
+    to: Option<u16>,
+}
+
+/// A validated transaction. Unlike `TransactionRecord`, each variant only
+/// carries the fields that are legal for it, so callers never need to
+/// re-check `Some`/`None` on `amount`.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(try_from = "TransactionRecord")]
+pub enum Transaction {
+    Deposit { client: u16, tx: u32, amount: Decimal },
+    Withdrawal { client: u16, tx: u32, amount: Decimal },
+    Dispute { client: u16, tx: u32 },
+    Resolve { client: u16, tx: u32 },
+    Chargeback { client: u16, tx: u32 },
+    Transfer { client: u16, to: u16, tx: u32, amount: Decimal },
+}
+
+/// Returned when a CSV row fails the per-type validation in `Transaction::try_from`.
+#[derive(Debug)]
+pub struct TransactionRecordError(String);
+
+impl fmt::Display for TransactionRecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TransactionRecordError {}
+
+impl TryFrom<TransactionRecord> for Transaction {
+    type Error = TransactionRecordError;
+
+    fn try_from(record: TransactionRecord) -> Result<Self, Self::Error> {
+        let TransactionRecord {
+            tx_type,
+            client,
+            tx,
+            amount,
+            to,
+        } = record;
+
+        if !matches!(tx_type, TransactionType::Transfer) && to.is_some() {
+            return Err(TransactionRecordError(format!(
+                "tx {} is not a transfer but specifies a destination client",
+                tx
+            )));
+        }
+
+        match tx_type {
+            TransactionType::Deposit => match amount {
+                Some(amount) => Ok(Transaction::Deposit { client, tx, amount }),
+                None => Err(TransactionRecordError(format!(
+                    "deposit tx {} is missing an amount",
+                    tx
+                ))),
+            },
+            TransactionType::Withdrawal => match amount {
+                Some(amount) => Ok(Transaction::Withdrawal { client, tx, amount }),
+                None => Err(TransactionRecordError(format!(
+                    "withdrawal tx {} is missing an amount",
+                    tx
+                ))),
+            },
+            TransactionType::Dispute => match amount {
+                None => Ok(Transaction::Dispute { client, tx }),
+                Some(_) => Err(TransactionRecordError(format!(
+                    "dispute tx {} must not carry an amount",
+                    tx
+                ))),
+            },
+            TransactionType::Resolve => match amount {
+                None => Ok(Transaction::Resolve { client, tx }),
+                Some(_) => Err(TransactionRecordError(format!(
+                    "resolve tx {} must not carry an amount",
+                    tx
+                ))),
+            },
+            TransactionType::Chargeback => match amount {
+                None => Ok(Transaction::Chargeback { client, tx }),
+                Some(_) => Err(TransactionRecordError(format!(
+                    "chargeback tx {} must not carry an amount",
+                    tx
+                ))),
+            },
+            TransactionType::Transfer => match (amount, to) {
+                (Some(amount), Some(to)) => Ok(Transaction::Transfer {
+                    client,
+                    to,
+                    tx,
+                    amount,
+                }),
+                (None, _) => Err(TransactionRecordError(format!(
+                    "transfer tx {} is missing an amount",
+                    tx
+                ))),
+                (Some(_), None) => Err(TransactionRecordError(format!(
+                    "transfer tx {} is missing a destination client",
+                    tx
+                ))),
+            },
+        }
+    }
+}
+
+/// Builds a `csv::ReaderBuilder` configured for transaction input: whitespace
+/// is trimmed, and `flexible(true)` so dispute/resolve/chargeback rows may
+/// omit the trailing `amount` column entirely instead of padding it with a
+/// trailing comma.
+pub fn transaction_reader_builder() -> ReaderBuilder {
+    let mut builder = ReaderBuilder::new();
+    builder.trim(csv::Trim::All).flexible(true);
+    builder
+}
+
+/// The lifecycle of a disputable transaction. The only legal transitions are
+/// `Processed -> Disputed`, `Disputed -> Resolved`, and `Disputed -> ChargedBack`;
+/// any other transition (e.g. disputing a `ChargedBack` tx) is rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TxState {
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+/// Whether a reversible transaction originally added funds to `available`
+/// (a deposit) or removed them (a withdrawal).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TxDirection {
+    Credit,
+    Debit,
+}
+
+#[derive(Debug)]
+struct ReversibleTx {
+    amount: Decimal,
+    direction: TxDirection,
+    state: TxState,
+}
+
+impl ReversibleTx {
+    /// The signed change this transaction made to `available` when it was
+    /// first processed: `+amount` for a deposit, `-amount` for a withdrawal.
+    /// Disputing a tx reverses this effect on `available` and mirrors it
+    /// into `held`; resolving undoes that; charging back finalizes it.
+    fn effect(&self) -> Decimal {
+        match self.direction {
+            TxDirection::Credit => self.amount,
+            TxDirection::Debit => -self.amount,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Account {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+    // History of reversible (deposit/withdrawal) transactions, keyed by transaction id.
+    history: HashMap<u32, ReversibleTx>,
+}
+
+impl Account {
+    fn new() -> Self {
+        Account {
+            available: Decimal::new(0, 4),
+            held: Decimal::new(0, 4),
+            locked: false,
+            history: HashMap::new(),
+        }
+    }
+}
+
+/// Errors produced while applying a single transaction to the ledger. These
+/// are per-transaction and never abort a batch; `process_records` reports
+/// them rather than discarding the offending row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerError {
+    NotEnoughFunds,
+    UnknownTx(u16, u32),
+    AlreadyDisputed,
+    NotDisputed,
+    FrozenAccount,
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerError::NotEnoughFunds => write!(f, "not enough available funds"),
+            LedgerError::UnknownTx(client, tx) => {
+                write!(f, "client {} has no tx {} to reference", client, tx)
+            }
+            LedgerError::AlreadyDisputed => write!(f, "tx is already disputed"),
+            LedgerError::NotDisputed => write!(f, "tx is not currently disputed"),
+            LedgerError::FrozenAccount => write!(f, "account is frozen"),
+        }
+    }
+}
+
+impl std::error::Error for LedgerError {}
+
+fn client_of(record: &Transaction) -> u16 {
+    match record {
+        Transaction::Deposit { client, .. }
+        | Transaction::Withdrawal { client, .. }
+        | Transaction::Dispute { client, .. }
+        | Transaction::Resolve { client, .. }
+        | Transaction::Chargeback { client, .. }
+        | Transaction::Transfer { client, .. } => *client,
+    }
+}
+
+/// Applies a single transaction to `accounts`, creating the account if needed.
+fn apply_transaction(
+    accounts: &mut HashMap<u16, Account>,
+    record: Transaction,
+) -> Result<(), LedgerError> {
+    let client = client_of(&record);
+    let account = accounts.entry(client).or_insert_with(Account::new);
+
+    if account.locked {
+        return Err(LedgerError::FrozenAccount);
+    }
+
+    match record {
+        Transaction::Deposit { tx, amount, .. } => {
+            account.available += amount;
+            account.history.insert(
+                tx,
+                ReversibleTx {
+                    amount,
+                    direction: TxDirection::Credit,
+                    state: TxState::Processed,
+                },
+            );
+            Ok(())
+        }
+        Transaction::Withdrawal { tx, amount, .. } => {
+            if account.available >= amount {
+                account.available -= amount;
+                account.history.insert(
+                    tx,
+                    ReversibleTx {
+                        amount,
+                        direction: TxDirection::Debit,
+                        state: TxState::Processed,
+                    },
+                );
+                Ok(())
+            } else {
+                Err(LedgerError::NotEnoughFunds)
+            }
+        }
+        Transaction::Dispute { tx, .. } => match account.history.get_mut(&tx) {
+            // Only `Processed -> Disputed` is a legal transition.
+            Some(entry) if entry.state == TxState::Processed => {
+                let effect = entry.effect();
+                account.available -= effect;
+                account.held += effect;
+                entry.state = TxState::Disputed;
+                Ok(())
+            }
+            Some(_) => Err(LedgerError::AlreadyDisputed),
+            None => Err(LedgerError::UnknownTx(client, tx)),
+        },
+        Transaction::Resolve { tx, .. } => match account.history.get_mut(&tx) {
+            // Only `Disputed -> Resolved` is a legal transition.
+            Some(entry) if entry.state == TxState::Disputed => {
+                let effect = entry.effect();
+                account.held -= effect;
+                account.available += effect;
+                entry.state = TxState::Resolved;
+                Ok(())
+            }
+            Some(_) => Err(LedgerError::NotDisputed),
+            None => Err(LedgerError::UnknownTx(client, tx)),
+        },
+        Transaction::Chargeback { tx, .. } => match account.history.get_mut(&tx) {
+            // Only `Disputed -> ChargedBack` is a legal transition.
+            Some(entry) if entry.state == TxState::Disputed => {
+                account.held -= entry.effect();
+                account.locked = true;
+                entry.state = TxState::ChargedBack;
+                Ok(())
+            }
+            Some(_) => Err(LedgerError::NotDisputed),
+            None => Err(LedgerError::UnknownTx(client, tx)),
+        },
+        Transaction::Transfer { to, amount, .. } => {
+            if accounts.get(&to).is_some_and(|a| a.locked) {
+                return Err(LedgerError::FrozenAccount);
+            }
+            // `client`'s account was already created above; look it up fresh
+            // rather than reusing `account` so the destination lookup below
+            // doesn't need to keep that borrow alive.
+            if accounts.get(&client).unwrap().available < amount {
+                return Err(LedgerError::NotEnoughFunds);
+            }
+            accounts.get_mut(&client).unwrap().available -= amount;
+            accounts.entry(to).or_insert_with(Account::new).available += amount;
+            Ok(())
+        }
+    }
+}
+
+/// Processes an iterator of transactions and returns the resulting accounts state.
+/// Individual failures (insufficient funds, a dispute referencing an unknown or
+/// already-resolved tx, etc.) are reported to stderr with a final count; they
+/// never abort the batch.
+pub fn process_records<I: Iterator<Item = Transaction>>(records: I) -> HashMap<u16, Account> {
+    let mut accounts: HashMap<u16, Account> = HashMap::new();
+    let mut error_count = 0usize;
+
+    for record in records {
+        if let Err(err) = apply_transaction(&mut accounts, record) {
+            error_count += 1;
+            eprintln!("failed to process transaction: {}", err);
+        }
+    }
+
+    if error_count > 0 {
+        eprintln!("{} transaction(s) failed to process", error_count);
+    }
+    accounts
+}
+
+/// How [`process_records_with`] should apply its input.
+#[derive(Debug, Clone, Copy)]
+pub enum ProcessingMode {
+    /// Single-threaded, as in [`process_records`]. The right default for
+    /// small inputs, where the cost of spinning up worker threads would
+    /// dwarf the cost of just processing the records.
+    Sequential,
+    /// Partitioned across `shard_count` worker threads by client id, as in
+    /// [`process_records_parallel`]. Worth it once the input is large
+    /// enough that per-client work can run concurrently.
+    Parallel { shard_count: usize },
+}
+
+/// Entry point that picks between [`process_records`] and
+/// [`process_records_parallel`] based on `mode`.
+pub fn process_records_with<I: Iterator<Item = Transaction>>(
+    records: I,
+    mode: ProcessingMode,
+) -> HashMap<u16, Account> {
+    match mode {
+        ProcessingMode::Sequential => process_records(records),
+        ProcessingMode::Parallel { shard_count } => process_records_parallel(records, shard_count),
+    }
+}
+
+/// The shard a given client's transactions are routed to, out of `shard_count`.
+fn shard_of(client: u16, shard_count: usize) -> usize {
+    (client as usize) % shard_count
+}
+
+/// Work dispatched to a shard worker thread.
+enum ShardJob {
+    /// A transaction whose client lives on this shard; applied directly to
+    /// this shard's account map.
+    Local(Transaction),
+    /// A rendezvous point used to serialize a cross-shard `Transfer`: the
+    /// worker signals `ready` once every job queued ahead of this one has
+    /// been applied, then blocks on `release` before resuming its queue.
+    /// See the dispatch loop in [`process_records_parallel`].
+    Quiesce {
+        ready: mpsc::Sender<()>,
+        release: mpsc::Receiver<()>,
+    },
+}
+
+/// Same as [`process_records`], but spreads work across `shard_count` worker
+/// threads, partitioned by client id: each shard owns a disjoint subset of
+/// clients and its own `HashMap<u16, Account>`, fed by an ordered channel
+/// from the dispatching (calling) thread. Because every transaction for a
+/// given client is routed to the same shard in the order it was read, the
+/// dispute/resolve/chargeback history `apply_transaction` relies on is
+/// exactly as consistent as in the sequential path.
+///
+/// The one operation that isn't contained to a single shard is `Transfer`:
+/// when its source and destination clients land on different shards, the
+/// dispatching thread first quiesces both shards — sending each a
+/// [`ShardJob::Quiesce`] and waiting for both to report `ready`, which
+/// happens only once every job queued ahead of it (i.e. every transaction
+/// that precedes the transfer in the input, for either client) has been
+/// applied. With both workers blocked, the transfer is applied directly
+/// across the two shards' maps (locked in ascending shard-index order, so
+/// two transfers crossing the same pair of shards can never deadlock on
+/// each other) before both workers are released to resume their queues.
+/// This gives the transfer the same happens-before relationship with every
+/// other transaction for `client` and `to` that the sequential path gets
+/// for free, instead of leaving it to thread scheduling.
+///
+/// Panics if `shard_count` is 0.
+pub fn process_records_parallel<I: Iterator<Item = Transaction>>(
+    records: I,
+    shard_count: usize,
+) -> HashMap<u16, Account> {
+    assert!(shard_count > 0, "shard_count must be at least 1");
+
+    let shards: Vec<Mutex<HashMap<u16, Account>>> = (0..shard_count)
+        .map(|_| Mutex::new(HashMap::new()))
+        .collect();
+    let (senders, receivers): (Vec<_>, Vec<_>) =
+        (0..shard_count).map(|_| mpsc::channel::<ShardJob>()).unzip();
+
+    let error_count: usize = thread::scope(|scope| {
+        let shards = &shards;
+        let handles: Vec<_> = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(shard_id, receiver)| {
+                scope.spawn(move || {
+                    let mut error_count = 0usize;
+                    for job in receiver {
+                        match job {
+                            ShardJob::Local(record) => {
+                                let mut accounts = shards[shard_id].lock().unwrap();
+                                if let Err(err) = apply_transaction(&mut accounts, record) {
+                                    error_count += 1;
+                                    eprintln!("failed to process transaction: {}", err);
+                                }
+                            }
+                            ShardJob::Quiesce { ready, release } => {
+                                let _ = ready.send(());
+                                let _ = release.recv();
+                            }
+                        }
+                    }
+                    error_count
+                })
+            })
+            .collect();
+
+        let mut cross_shard_error_count = 0usize;
+        for record in records {
+            match record {
+                Transaction::Transfer { client, to, amount, .. }
+                    if shard_of(client, shard_count) != shard_of(to, shard_count) =>
+                {
+                    let from_shard = shard_of(client, shard_count);
+                    let to_shard = shard_of(to, shard_count);
+
+                    let (from_ready_tx, from_ready_rx) = mpsc::channel();
+                    let (from_release_tx, from_release_rx) = mpsc::channel();
+                    senders[from_shard]
+                        .send(ShardJob::Quiesce { ready: from_ready_tx, release: from_release_rx })
+                        .expect("shard worker thread terminated early");
+
+                    let (to_ready_tx, to_ready_rx) = mpsc::channel();
+                    let (to_release_tx, to_release_rx) = mpsc::channel();
+                    senders[to_shard]
+                        .send(ShardJob::Quiesce { ready: to_ready_tx, release: to_release_rx })
+                        .expect("shard worker thread terminated early");
+
+                    from_ready_rx.recv().expect("shard worker thread terminated early");
+                    to_ready_rx.recv().expect("shard worker thread terminated early");
+
+                    if let Err(err) =
+                        apply_transfer_across_shards(shards, shard_count, client, to, amount)
+                    {
+                        cross_shard_error_count += 1;
+                        eprintln!("failed to process transaction: {}", err);
+                    }
+
+                    let _ = from_release_tx.send(());
+                    let _ = to_release_tx.send(());
+                }
+                other => {
+                    let shard = shard_of(client_of(&other), shard_count);
+                    senders[shard]
+                        .send(ShardJob::Local(other))
+                        .expect("shard worker thread terminated early");
+                }
+            }
+        }
+        drop(senders);
+
+        handles.into_iter().map(|h| h.join().unwrap()).sum::<usize>() + cross_shard_error_count
+    });
+
+    if error_count > 0 {
+        eprintln!("{} transaction(s) failed to process", error_count);
+    }
+
+    let mut accounts = HashMap::new();
+    for shard in shards {
+        accounts.extend(shard.into_inner().unwrap());
+    }
+    accounts
+}
+
+/// Applies a `Transfer` whose source and destination clients hash to
+/// different shards, locking both shards' maps for the duration. Called only
+/// once the dispatching thread has quiesced both shards (see
+/// `process_records_parallel`), so the locks are uncontended here; they're
+/// kept anyway so this function's own invariants don't depend on that
+/// caller detail. Lock order is always ascending by shard index, regardless
+/// of transfer direction, so this can never deadlock against a concurrent
+/// transfer between the same two shards.
+fn apply_transfer_across_shards(
+    shards: &[Mutex<HashMap<u16, Account>>],
+    shard_count: usize,
+    client: u16,
+    to: u16,
+    amount: Decimal,
+) -> Result<(), LedgerError> {
+    let from_shard = shard_of(client, shard_count);
+    let to_shard = shard_of(to, shard_count);
+    debug_assert_ne!(from_shard, to_shard);
+
+    let (lower, upper) = if from_shard < to_shard {
+        (from_shard, to_shard)
+    } else {
+        (to_shard, from_shard)
+    };
+    let mut lower_guard = shards[lower].lock().unwrap();
+    let mut upper_guard = shards[upper].lock().unwrap();
+    let (from_accounts, to_accounts) = if from_shard < to_shard {
+        (&mut *lower_guard, &mut *upper_guard)
+    } else {
+        (&mut *upper_guard, &mut *lower_guard)
+    };
+
+    let source = from_accounts.entry(client).or_insert_with(Account::new);
+    if to_accounts.get(&to).is_some_and(|a| a.locked) {
+        return Err(LedgerError::FrozenAccount);
+    }
+    if source.locked {
+        return Err(LedgerError::FrozenAccount);
+    }
+    if source.available < amount {
+        return Err(LedgerError::NotEnoughFunds);
+    }
+    source.available -= amount;
+    to_accounts.entry(to).or_insert_with(Account::new).available += amount;
+    Ok(())
+}
+
+/// One row of `dump_csv`'s output: an account's balances rounded to 4
+/// decimal places (half-even, so `2.74245` rounds to `2.7424` the same way
+/// every time, not however the platform's default float formatting
+/// happens to truncate it), plus its `locked` state.
+#[derive(Debug, Serialize)]
+struct AccountRow {
+    client: u16,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+}
+
+/// Rounds `amount` to 4 decimal places using banker's rounding (round
+/// half to even) and formats it with exactly 4 digits after the point.
+fn format_amount(amount: Decimal) -> String {
+    let rounded = amount.round_dp_with_strategy(4, RoundingStrategy::MidpointNearestEven);
+    format!("{:.4}", rounded)
+}
+
+/// Writes `accounts` to `writer` as CSV: a `client,available,held,total,locked`
+/// header followed by one row per account, sorted by client id so output is
+/// deterministic across runs regardless of the `HashMap`'s iteration order.
+pub fn dump_csv<W: Write>(accounts: &HashMap<u16, Account>, writer: W) -> csv::Result<()> {
+    let mut wtr = WriterBuilder::new().has_headers(true).from_writer(writer);
+
+    for (&client, account) in accounts.iter().collect::<BTreeMap<_, _>>() {
+        wtr.serialize(AccountRow {
+            client,
+            available: format_amount(account.available),
+            held: format_amount(account.held),
+            total: format_amount(account.available + account.held),
+            locked: account.locked,
+        })?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deposit() {
+        let client_id = 1u16;
+        let records = vec![Transaction::Deposit {
+            client: client_id,
+            tx: 1,
+            amount: Decimal::new(10, 1),
+        }];
+        let accounts = process_records(records.into_iter());
+        let account = accounts.get(&client_id).unwrap();
+        assert_eq!(account.available, Decimal::new(10, 1));
+        assert_eq!(account.held, Decimal::new(0, 4));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_deposit_multi_account() {
+        let client_id = 1u16;
+        let client_id_2 = 2u16;
+
+        let records = vec![
+            Transaction::Deposit {
+                client: client_id,
+                tx: 1,
+                amount: Decimal::new(10, 1),
+            },
+            Transaction::Deposit {
+                client: client_id_2,
+                tx: 1,
+                amount: Decimal::new(20, 1),
+            },
+        ];
+        let accounts = process_records(records.into_iter());
+
+        let account = accounts.get(&client_id).unwrap();
+        assert_eq!(account.available, Decimal::new(10, 1));
+        assert_eq!(account.held, Decimal::new(0, 4));
+        assert!(!account.locked);
+
+        let account = accounts.get(&client_id_2).unwrap();
+        assert_eq!(account.available, Decimal::new(20, 1));
+        assert_eq!(account.held, Decimal::new(0, 4));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_withdrawal_success() {
+        let client_id = 1u16;
+        let records = vec![
+            Transaction::Deposit {
+                client: client_id,
+                tx: 1,
+                amount: Decimal::new(20, 1),
+            },
+            Transaction::Withdrawal {
+                client: client_id,
+                tx: 2,
+                amount: Decimal::new(15, 1),
+            },
+        ];
+        let accounts = process_records(records.into_iter());
+        let account = accounts.get(&client_id).unwrap();
+        assert_eq!(account.available, Decimal::new(5, 1));
+        assert_eq!(account.held, Decimal::new(0, 4));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_withdrawal_insufficient_funds() {
+        let client_id = 1u16;
+        let records = vec![
+            Transaction::Deposit {
+                client: client_id,
+                tx: 1,
+                amount: Decimal::new(10, 1),
+            },
+            Transaction::Withdrawal {
+                client: client_id,
+                tx: 2,
+                amount: Decimal::new(15, 1),
+            },
+        ];
+        let accounts = process_records(records.into_iter());
+        let account = accounts.get(&client_id).unwrap();
+        // Since funds are insufficient, the withdrawal should not occur.
+        assert_eq!(account.available, Decimal::new(10, 1));
+        assert_eq!(account.held, Decimal::new(0, 4));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_dispute_resolve_chargeback() {
+        let records = vec![
+            Transaction::Deposit {
+                client: 1,
+                tx: 10,
+                amount: Decimal::new(20, 1),
+            },
+            Transaction::Dispute { client: 1, tx: 10 },
+            Transaction::Chargeback { client: 1, tx: 10 },
+        ];
+
+        let accounts = process_records(records.into_iter());
+        let account = accounts.get(&1).unwrap();
+
+        assert_eq!(account.available, Decimal::new(0, 4));
+        assert_eq!(account.held, Decimal::new(0, 4));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_dispute_unknown_tx_is_ignored() {
+        let records = vec![
+            Transaction::Deposit {
+                client: 1,
+                tx: 10,
+                amount: Decimal::new(20, 1),
+            },
+            Transaction::Dispute { client: 1, tx: 99 },
+        ];
+
+        let accounts = process_records(records.into_iter());
+        let account = accounts.get(&1).unwrap();
+
+        assert_eq!(account.available, Decimal::new(20, 1));
+        assert_eq!(account.held, Decimal::new(0, 4));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_redispute_after_chargeback_is_ignored() {
+        let records = vec![
+            Transaction::Deposit {
+                client: 1,
+                tx: 10,
+                amount: Decimal::new(20, 1),
+            },
+            Transaction::Dispute { client: 1, tx: 10 },
+            Transaction::Chargeback { client: 1, tx: 10 },
+            Transaction::Dispute { client: 1, tx: 10 },
+        ];
+
+        let accounts = process_records(records.into_iter());
+        let account = accounts.get(&1).unwrap();
+
+        // The second dispute on an already-charged-back tx must be a no-op.
+        assert_eq!(account.available, Decimal::new(0, 4));
+        assert_eq!(account.held, Decimal::new(0, 4));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_deposit_missing_amount_is_rejected() {
+        let record = TransactionRecord {
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: None,
+            to: None,
+        };
+        assert!(Transaction::try_from(record).is_err());
+    }
+
+    #[test]
+    fn test_withdrawal_insufficient_funds_returns_error() {
+        let mut accounts = HashMap::new();
+        apply_transaction(
+            &mut accounts,
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::new(10, 1),
+            },
+        )
+        .unwrap();
+        let err = apply_transaction(
+            &mut accounts,
+            Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Decimal::new(15, 1),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err, LedgerError::NotEnoughFunds);
+    }
+
+    #[test]
+    fn test_dispute_unknown_tx_returns_error() {
+        let mut accounts = HashMap::new();
+        let err =
+            apply_transaction(&mut accounts, Transaction::Dispute { client: 1, tx: 99 }).unwrap_err();
+        assert_eq!(err, LedgerError::UnknownTx(1, 99));
+    }
+
+    #[test]
+    fn test_resolve_without_dispute_returns_error() {
+        let mut accounts = HashMap::new();
+        apply_transaction(
+            &mut accounts,
+            Transaction::Deposit {
+                client: 1,
+                tx: 10,
+                amount: Decimal::new(20, 1),
+            },
+        )
+        .unwrap();
+        let err =
+            apply_transaction(&mut accounts, Transaction::Resolve { client: 1, tx: 10 }).unwrap_err();
+        assert_eq!(err, LedgerError::NotDisputed);
+    }
+
+    #[test]
+    fn test_transaction_on_frozen_account_returns_error() {
+        let mut accounts = HashMap::new();
+        apply_transaction(
+            &mut accounts,
+            Transaction::Deposit {
+                client: 1,
+                tx: 10,
+                amount: Decimal::new(20, 1),
+            },
+        )
+        .unwrap();
+        apply_transaction(&mut accounts, Transaction::Dispute { client: 1, tx: 10 }).unwrap();
+        apply_transaction(&mut accounts, Transaction::Chargeback { client: 1, tx: 10 }).unwrap();
+        let err = apply_transaction(
+            &mut accounts,
+            Transaction::Deposit {
+                client: 1,
+                tx: 11,
+                amount: Decimal::new(5, 1),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err, LedgerError::FrozenAccount);
+    }
+
+    #[test]
+    fn test_dispute_withdrawal_holds_negative() {
+        // Disputing a withdrawal reverses its effect on `available` (giving the
+        // funds back) and mirrors that into `held`, which goes negative: the
+        // account is on the hook for the amount if the dispute is upheld.
+        let records = vec![
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::new(100, 1),
+            },
+            Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Decimal::new(40, 1),
+            },
+            Transaction::Dispute { client: 1, tx: 2 },
+        ];
+
+        let accounts = process_records(records.into_iter());
+        let account = accounts.get(&1).unwrap();
+
+        assert_eq!(account.available, Decimal::new(100, 1));
+        assert_eq!(account.held, Decimal::new(-40, 1));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_resolve_disputed_withdrawal() {
+        // Resolving a disputed withdrawal undoes the dispute, taking the funds
+        // back out of `available` and restoring `held` to zero.
+        let records = vec![
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::new(100, 1),
+            },
+            Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Decimal::new(40, 1),
+            },
+            Transaction::Dispute { client: 1, tx: 2 },
+            Transaction::Resolve { client: 1, tx: 2 },
+        ];
+
+        let accounts = process_records(records.into_iter());
+        let account = accounts.get(&1).unwrap();
+
+        assert_eq!(account.available, Decimal::new(60, 1));
+        assert_eq!(account.held, Decimal::new(0, 4));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_chargeback_disputed_withdrawal() {
+        // A chargeback on a disputed withdrawal confirms it was unauthorized:
+        // the withdrawn funds stay in `available`, `held` returns to zero,
+        // and the account is frozen.
+        let records = vec![
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::new(100, 1),
+            },
+            Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Decimal::new(40, 1),
+            },
+            Transaction::Dispute { client: 1, tx: 2 },
+            Transaction::Chargeback { client: 1, tx: 2 },
+        ];
+
+        let accounts = process_records(records.into_iter());
+        let account = accounts.get(&1).unwrap();
+
+        assert_eq!(account.available, Decimal::new(100, 1));
+        assert_eq!(account.held, Decimal::new(0, 4));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_dispute_with_stray_amount_is_rejected() {
+        let record = TransactionRecord {
+            tx_type: TransactionType::Dispute,
+            client: 1,
+            tx: 1,
+            amount: Some(Decimal::new(10, 1)),
+            to: None,
+        };
+        assert!(Transaction::try_from(record).is_err());
+    }
+
+    #[test]
+    fn test_transfer_insufficient_funds() {
+        let mut accounts = HashMap::new();
+        apply_transaction(
+            &mut accounts,
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::new(10, 1),
+            },
+        )
+        .unwrap();
+        let err = apply_transaction(
+            &mut accounts,
+            Transaction::Transfer {
+                client: 1,
+                to: 2,
+                tx: 2,
+                amount: Decimal::new(15, 1),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err, LedgerError::NotEnoughFunds);
+
+        let source = accounts.get(&1).unwrap();
+        assert_eq!(source.available, Decimal::new(10, 1));
+        assert!(!accounts.contains_key(&2));
+    }
+
+    #[test]
+    fn test_transfer_to_new_client() {
+        let records = vec![
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::new(100, 1),
+            },
+            Transaction::Transfer {
+                client: 1,
+                to: 2,
+                tx: 2,
+                amount: Decimal::new(40, 1),
+            },
+        ];
+
+        let accounts = process_records(records.into_iter());
+
+        let source = accounts.get(&1).unwrap();
+        assert_eq!(source.available, Decimal::new(60, 1));
+        assert!(!source.locked);
+
+        let dest = accounts.get(&2).unwrap();
+        assert_eq!(dest.available, Decimal::new(40, 1));
+        assert_eq!(dest.held, Decimal::new(0, 4));
+        assert!(!dest.locked);
+    }
+
+    #[test]
+    fn test_transfer_to_frozen_account_is_rejected() {
+        let mut accounts = HashMap::new();
+        apply_transaction(
+            &mut accounts,
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::new(100, 1),
+            },
+        )
+        .unwrap();
+        apply_transaction(
+            &mut accounts,
+            Transaction::Deposit {
+                client: 2,
+                tx: 2,
+                amount: Decimal::new(20, 1),
+            },
+        )
+        .unwrap();
+        apply_transaction(&mut accounts, Transaction::Dispute { client: 2, tx: 2 }).unwrap();
+        apply_transaction(&mut accounts, Transaction::Chargeback { client: 2, tx: 2 }).unwrap();
+
+        let err = apply_transaction(
+            &mut accounts,
+            Transaction::Transfer {
+                client: 1,
+                to: 2,
+                tx: 3,
+                amount: Decimal::new(10, 1),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err, LedgerError::FrozenAccount);
+
+        let source = accounts.get(&1).unwrap();
+        assert_eq!(source.available, Decimal::new(100, 1));
+    }
+
+    #[test]
+    fn test_transfer_from_frozen_account_is_rejected() {
+        let mut accounts = HashMap::new();
+        apply_transaction(
+            &mut accounts,
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Decimal::new(100, 1),
+            },
+        )
+        .unwrap();
+        apply_transaction(&mut accounts, Transaction::Dispute { client: 1, tx: 1 }).unwrap();
+        apply_transaction(&mut accounts, Transaction::Chargeback { client: 1, tx: 1 }).unwrap();
+
+        let err = apply_transaction(
+            &mut accounts,
+            Transaction::Transfer {
+                client: 1,
+                to: 2,
+                tx: 2,
+                amount: Decimal::new(10, 1),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err, LedgerError::FrozenAccount);
+        assert!(!accounts.contains_key(&2));
+    }
+
+    /// A batch spanning several clients, including a transfer that crosses
+    /// shard boundaries under a small shard count (0 -> 3 with 4 shards; the
+    /// 5 -> 1 transfer stays within one shard there).
+    fn sample_sharded_records() -> Vec<Transaction> {
+        let mut records = Vec::new();
+        for client in 0u16..6 {
+            let base = u32::from(client) * 10;
+            records.push(Transaction::Deposit {
+                client,
+                tx: base + 1,
+                amount: Decimal::new(1000, 2),
+            });
+            records.push(Transaction::Withdrawal {
+                client,
+                tx: base + 2,
+                amount: Decimal::new(200, 2),
+            });
+            records.push(Transaction::Dispute {
+                client,
+                tx: base + 1,
+            });
+            records.push(Transaction::Resolve {
+                client,
+                tx: base + 1,
+            });
+        }
+        records.push(Transaction::Transfer {
+            client: 0,
+            to: 3,
+            tx: 1001,
+            amount: Decimal::new(100, 2),
+        });
+        records.push(Transaction::Transfer {
+            client: 5,
+            to: 1,
+            tx: 1002,
+            amount: Decimal::new(50, 2),
+        });
+        records
+    }
+
+    /// The same transactions as `sample_sharded_records`, but interleaved
+    /// round-robin across clients instead of grouped by client — the shape
+    /// a real multi-client input stream takes. Each client's deposit,
+    /// withdrawal, dispute and resolve keep their relative order. The two
+    /// transfers sit in lanes of their own, so they land in the first round,
+    /// right after the deposits and ahead of their clients' withdrawals; no
+    /// transaction fails under either order, so the final balances agree.
+    fn interleaved_sharded_records() -> Vec<Transaction> {
+        let per_client: Vec<Vec<Transaction>> = (0u16..6)
+            .map(|client| {
+                let base = u32::from(client) * 10;
+                vec![
+                    Transaction::Deposit {
+                        client,
+                        tx: base + 1,
+                        amount: Decimal::new(1000, 2),
+                    },
+                    Transaction::Withdrawal {
+                        client,
+                        tx: base + 2,
+                        amount: Decimal::new(200, 2),
+                    },
+                    Transaction::Dispute {
+                        client,
+                        tx: base + 1,
+                    },
+                    Transaction::Resolve {
+                        client,
+                        tx: base + 1,
+                    },
+                ]
+            })
+            .chain([
+                vec![Transaction::Transfer {
+                    client: 0,
+                    to: 3,
+                    tx: 1001,
+                    amount: Decimal::new(100, 2),
+                }],
+                vec![Transaction::Transfer {
+                    client: 5,
+                    to: 1,
+                    tx: 1002,
+                    amount: Decimal::new(50, 2),
+                }],
+            ])
+            .collect();
+
+        let mut lanes: Vec<_> = per_client.into_iter().map(Vec::into_iter).collect();
+        let mut result = Vec::new();
+        loop {
+            let mut advanced = false;
+            for lane in &mut lanes {
+                if let Some(record) = lane.next() {
+                    result.push(record);
+                    advanced = true;
+                }
+            }
+            if !advanced {
+                break;
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential_on_shuffled_stream() {
+        let sequential = process_records(sample_sharded_records().into_iter());
+        let parallel = process_records_parallel(interleaved_sharded_records().into_iter(), 4);
+
+        assert_eq!(sequential.len(), parallel.len());
+        for (client, account) in &sequential {
+            let other = parallel
+                .get(client)
+                .expect("client missing from parallel result");
+            assert_eq!(account.available, other.available);
+            assert_eq!(account.held, other.held);
+            assert_eq!(account.locked, other.locked);
+        }
+    }
+
+    /// The race from the request's review: a deposit and a cross-shard
+    /// transfer for client 0, followed by a withdrawal for client 1 (the
+    /// transfer's destination, on a different shard) that only has
+    /// sufficient funds once the transfer's credit has landed. Regression
+    /// test for a bug where the credit was applied out-of-band from the
+    /// destination shard's own channel, so whether it landed before or
+    /// after the withdrawal depended on thread scheduling rather than input
+    /// order.
+    fn cross_shard_transfer_then_dependent_withdrawal() -> Vec<Transaction> {
+        vec![
+            Transaction::Deposit {
+                client: 0,
+                tx: 1,
+                amount: Decimal::new(1000, 2),
+            },
+            Transaction::Transfer {
+                client: 0,
+                to: 1,
+                tx: 2,
+                amount: Decimal::new(500, 2),
+            },
+            Transaction::Withdrawal {
+                client: 1,
+                tx: 3,
+                amount: Decimal::new(500, 2),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_parallel_cross_shard_transfer_orders_before_dependent_withdrawal() {
+        for _ in 0..50 {
+            let accounts =
+                process_records_parallel(cross_shard_transfer_then_dependent_withdrawal().into_iter(), 2);
+            let dest = accounts.get(&1).expect("client 1 missing from result");
+            assert_eq!(dest.available, Decimal::new(0, 2));
+            assert!(!dest.locked);
+        }
+    }
+
+    #[test]
+    fn test_process_records_with_sequential_matches_process_records() {
+        let via_default = process_records(sample_sharded_records().into_iter());
+        let via_mode = process_records_with(
+            sample_sharded_records().into_iter(),
+            ProcessingMode::Sequential,
+        );
+        assert_eq!(via_default.len(), via_mode.len());
+        for (client, account) in &via_default {
+            let other = via_mode
+                .get(client)
+                .expect("client missing from process_records_with result");
+            assert_eq!(account.available, other.available);
+            assert_eq!(account.held, other.held);
+            assert_eq!(account.locked, other.locked);
+        }
+    }
+
+    #[test]
+    fn test_dump_csv_sorts_by_client_id() {
+        let mut accounts = HashMap::new();
+        accounts.insert(3u16, Account::new());
+        accounts.insert(1u16, Account::new());
+        accounts.insert(2u16, Account::new());
+
+        let mut out = Vec::new();
+        dump_csv(&accounts, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        let client_column: Vec<&str> = text
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert_eq!(client_column, vec!["1", "2", "3"]);
+    }
+
+    #[test]
+    fn test_dump_csv_rounds_half_to_even() {
+        let mut account = Account::new();
+        account.available = Decimal::new(274245, 5); // 2.74245 -> 2.7424, not 2.7425
+        let mut accounts = HashMap::new();
+        accounts.insert(1u16, account);
+
+        let mut out = Vec::new();
+        dump_csv(&accounts, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert!(text.contains("1,2.7424,0.0000,2.7424,false"));
+    }
+}